@@ -0,0 +1,774 @@
+//! A parser for FreeBSD's
+//! [jail.conf(5)](https://www.freebsd.org/cgi/man.cgi?query=jail.conf&sektion=5)
+//! C-style block configuration format.
+//!
+//! This lets a user load `/etc/jail.conf` (or any file in the same format)
+//! directly into a `Vec<`[`StoppedJail`](../struct.StoppedJail.html)`>`
+//! instead of constructing each jail by hand, then call `.start()` on the
+//! ones they want running.
+//!
+//! Only the subset of the format needed to populate a `StoppedJail` is
+//! understood: top-level `param = value;` defaults, named `name { ... }`
+//! blocks, the `*` wildcard block, `+=` list append, bare `key;` booleans,
+//! `$name`/`${name}` variable interpolation, quoted values (so a `;`, `#`
+//! or `{`/`}` inside a quoted string isn't mistaken for a delimiter) and
+//! `/* */` / `#` comments.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use param;
+use JailError;
+use StoppedJail;
+
+/// Parse the contents of a `jail.conf(5)` file into a list of
+/// [`StoppedJail`](../struct.StoppedJail.html)s.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jail::config;
+///
+/// let conf = std::fs::read_to_string("/etc/jail.conf").unwrap();
+/// let jails = config::parse(&conf).expect("could not parse jail.conf");
+///
+/// for jail in jails {
+///     jail.start().expect("could not start jail");
+/// }
+/// ```
+pub fn parse(input: &str) -> Result<Vec<StoppedJail>, JailError> {
+    let tokens = tokenize(input)?;
+    let blocks = group_blocks(tokens)?;
+
+    let mut globals: HashMap<String, RawValue> = HashMap::new();
+    let mut wildcard: HashMap<String, RawValue> = HashMap::new();
+    let mut named: Vec<(String, HashMap<String, RawValue>)> = vec![];
+
+    for block in blocks {
+        match block.name.as_str() {
+            // A bare `key = value;` at the top level with no following
+            // block is folded into `globals` by `group_blocks`.
+            "" => apply_statements(&mut globals, block.statements)?,
+            "*" => apply_statements(&mut wildcard, block.statements)?,
+            name => {
+                let mut params = globals.clone();
+                apply_statements(&mut params, block.statements)?;
+                named.push((name.to_string(), params));
+            }
+        }
+    }
+
+    let mut jails = Vec::with_capacity(named.len());
+    for (name, mut params) in named {
+        // The wildcard block supplies defaults, but explicit per-jail
+        // settings (including our own top-level defaults) win.
+        for (k, v) in &wildcard {
+            params.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+
+        interpolate(&mut params, &name)?;
+        jails.push(to_stopped_jail(name, params)?);
+    }
+
+    Ok(jails)
+}
+
+/// A parameter value before it has been bound to a `StoppedJail` field.
+///
+/// Scalar and list entries carry the line they were assigned/appended on,
+/// so a later validation error (e.g. an unparsable IP address) can point
+/// at the statement that produced the bad value.
+#[derive(Clone, Debug)]
+enum RawValue {
+    Bool(bool),
+    Scalar(String, usize),
+    List(Vec<(String, usize)>),
+}
+
+struct Block {
+    name: String,
+    statements: Vec<Statement>,
+}
+
+#[derive(Clone, Debug)]
+enum Statement {
+    /// `key;`, on the given line
+    Flag(String, usize),
+    /// `key = value;`, on the given line
+    Assign(String, String, usize),
+    /// `key += value;`, on the given line
+    Append(String, String, usize),
+}
+
+/// Find the first occurrence of one of `needles` that is not inside a
+/// double-quoted string, honoring `\`-escapes within quotes.
+fn find_unquoted(s: &str, needles: &[char]) -> Option<usize> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if !in_quotes && needles.contains(&c) => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Split `s` on `delim`, skipping any `delim` that falls inside a
+/// double-quoted string.
+fn split_unquoted(s: &str, delim: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c == delim && !in_quotes => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Byte offset of `sub` within `src`, assuming `sub` is a sub-slice of
+/// `src` (as every substring handed around by the grouping/statement
+/// parsing below is).
+fn offset_in(src: &str, sub: &str) -> usize {
+    sub.as_ptr() as usize - src.as_ptr() as usize
+}
+
+/// The 1-based line number of the given byte offset into `src`.
+fn line_at(src: &str, offset: usize) -> usize {
+    1 + src.as_bytes()[..offset]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+}
+
+/// Strip a matching pair of surrounding double quotes, if present, and
+/// drop the `\` of every `\`-escape (so `\"` becomes `"`, `\\` becomes `\`
+/// and e.g. `\n` becomes `n`).
+fn unquote(s: &str) -> String {
+    let s = s.trim();
+
+    let inner = if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    };
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                out.push(next);
+                continue;
+            }
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+/// Strip comments from `input`, returning the remaining text (with every
+/// newline kept, so line numbers still match the input) for
+/// `group_blocks` to split into statements and blocks. Comment markers
+/// inside a quoted string are left alone.
+fn tokenize(input: &str) -> Result<String, JailError> {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+    let mut in_quotes = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                out.push(c);
+            }
+            '\\' if in_quotes => {
+                out.push(c);
+                if let Some(n) = chars.next() {
+                    if n == '\n' {
+                        line += 1;
+                    }
+                    out.push(n);
+                }
+            }
+            '\n' => {
+                line += 1;
+                out.push('\n');
+            }
+            '#' if !in_quotes => {
+                while let Some(&n) = chars.peek() {
+                    if n == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '/' if !in_quotes && chars.peek() == Some(&'*') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        None => {
+                            return Err(JailError::ConfigParseError {
+                                line,
+                                msg: "unterminated /* comment".to_string(),
+                            })
+                        }
+                        Some('\n') => {
+                            line += 1;
+                            out.push('\n');
+                        }
+                        Some('*') if chars.peek() == Some(&'/') => {
+                            chars.next();
+                            break;
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+fn group_blocks(src: String) -> Result<Vec<Block>, JailError> {
+    let mut blocks = vec![];
+    let mut top_level = vec![];
+
+    let mut rest = src.as_str();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        match find_unquoted(rest, &['{', ';']) {
+            Some(pos) if rest.as_bytes()[pos] == b'{' => {
+                let name = rest[..pos].trim().to_string();
+                let brace_line = line_at(&src, offset_in(&src, rest) + pos);
+                let close = find_matching_brace(&rest[pos..], brace_line)?;
+                let body = &rest[pos + 1..pos + close];
+                let statements = parse_statements(body, &src)?;
+                blocks.push(Block { name, statements });
+                rest = &rest[pos + close + 1..];
+            }
+            Some(pos) => {
+                let stmt = &rest[..pos];
+                top_level.extend(parse_statements(stmt, &src)?);
+                rest = &rest[pos + 1..];
+            }
+            None => break,
+        }
+    }
+
+    if !top_level.is_empty() {
+        blocks.insert(
+            0,
+            Block {
+                name: String::new(),
+                statements: top_level,
+            },
+        );
+    }
+
+    Ok(blocks)
+}
+
+/// Find the index (relative to the start of `s`, which must begin with
+/// `{`) of the `}` that closes it, skipping braces inside quoted strings.
+/// `start_line` is the line number of the opening `{`, reported back if no
+/// matching `}` is found.
+fn find_matching_brace(s: &str, start_line: usize) -> Result<usize, JailError> {
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '{' if !in_quotes => depth += 1,
+            '}' if !in_quotes => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Err(JailError::ConfigParseError {
+        line: start_line,
+        msg: "unterminated block (missing '}')".to_string(),
+    })
+}
+
+/// `src` is the full tokenized input that `body` is a sub-slice of, needed
+/// to recover each statement's line number for error reporting.
+fn parse_statements(body: &str, src: &str) -> Result<Vec<Statement>, JailError> {
+    split_unquoted(body, ';')
+        .into_iter()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|stmt| {
+            let line = line_at(src, offset_in(src, stmt));
+
+            let eq = match find_unquoted(stmt, &['=']) {
+                Some(pos) => pos,
+                None => return Ok(Statement::Flag(stmt.to_string(), line)),
+            };
+
+            let is_append = eq > 0 && stmt.as_bytes()[eq - 1] == b'+';
+            let key_end = if is_append { eq - 1 } else { eq };
+            let key = stmt[..key_end].trim().to_string();
+            let val = unquote(&stmt[eq + 1..]);
+
+            if is_append {
+                Ok(Statement::Append(key, val, line))
+            } else {
+                Ok(Statement::Assign(key, val, line))
+            }
+        })
+        .collect()
+}
+
+fn apply_statements(
+    params: &mut HashMap<String, RawValue>,
+    statements: Vec<Statement>,
+) -> Result<(), JailError> {
+    for statement in statements {
+        match statement {
+            Statement::Flag(key, _line) => {
+                params.insert(key, RawValue::Bool(true));
+            }
+            Statement::Assign(key, val, line) => {
+                params.insert(key, RawValue::Scalar(val, line));
+            }
+            Statement::Append(key, val, line) => {
+                let entry = params.entry(key).or_insert_with(|| RawValue::List(vec![]));
+                match entry {
+                    RawValue::List(list) => list.push((val, line)),
+                    RawValue::Scalar(existing, existing_line) => {
+                        *entry =
+                            RawValue::List(vec![(existing.clone(), *existing_line), (val, line)]);
+                    }
+                    RawValue::Bool(_) => {
+                        *entry = RawValue::List(vec![(val, line)]);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Expand `$var` and `${var}` references. `$name` and `${name}` expand to
+/// the jail's own name; any other variable expands to the scalar value of
+/// the jail's parameter of that name. An unknown bare `$var` is left as
+/// written, while an unknown `${var}` expands to nothing. List-valued
+/// parameters are not substituted.
+fn interpolate(params: &mut HashMap<String, RawValue>, name: &str) -> Result<(), JailError> {
+    let snapshot = params.clone();
+
+    let expand = |s: &str| -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                out.push(c);
+                continue;
+            }
+
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                let mut var = String::new();
+                while let Some(&n) = chars.peek() {
+                    if n == '}' {
+                        chars.next();
+                        break;
+                    }
+                    var.push(n);
+                    chars.next();
+                }
+
+                if var == "name" {
+                    out.push_str(name);
+                } else if let Some(RawValue::Scalar(v, _)) = snapshot.get(&var) {
+                    out.push_str(v);
+                }
+            } else {
+                let mut var = String::new();
+                while let Some(&n) = chars.peek() {
+                    if n.is_alphanumeric() || n == '_' || n == '.' {
+                        var.push(n);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                if var == "name" {
+                    out.push_str(name);
+                } else if let Some(RawValue::Scalar(v, _)) = snapshot.get(&var) {
+                    out.push_str(v);
+                } else {
+                    out.push('$');
+                    out.push_str(&var);
+                }
+            }
+        }
+
+        out
+    };
+
+    for value in params.values_mut() {
+        match value {
+            RawValue::Scalar(s, _line) => *s = expand(s),
+            RawValue::List(list) => {
+                for (item, _line) in list.iter_mut() {
+                    *item = expand(item);
+                }
+            }
+            RawValue::Bool(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Map the well-known keys onto `StoppedJail` fields, and everything else
+/// into its `params` map, inferring `Int` vs `String` vs list.
+fn to_stopped_jail(
+    name: String,
+    params: HashMap<String, RawValue>,
+) -> Result<StoppedJail, JailError> {
+    let mut jail = StoppedJail::default().name(name);
+
+    for (key, value) in params {
+        match key.as_str() {
+            "path" => {
+                if let RawValue::Scalar(s, _line) = value {
+                    jail = StoppedJail {
+                        path: Some(s.into()),
+                        ..jail
+                    };
+                }
+            }
+            "host.hostname" => {
+                if let RawValue::Scalar(s, _line) = value {
+                    jail = jail.hostname(s);
+                }
+            }
+            "ip4.addr" | "ip6.addr" => {
+                let addrs = match value {
+                    RawValue::Scalar(s, line) => vec![(s, line)],
+                    RawValue::List(l) => l,
+                    RawValue::Bool(_) => vec![],
+                };
+
+                for (addr, line) in addrs {
+                    for part in addr.split(',') {
+                        let part = part.trim();
+                        if part.is_empty() {
+                            continue;
+                        }
+
+                        let ip: IpAddr = part.parse().map_err(|_| JailError::ConfigParseError {
+                            line,
+                            msg: format!("invalid IP address '{}' for {}", part, key),
+                        })?;
+
+                        jail = jail.ip(ip);
+                    }
+                }
+            }
+            _ => {
+                jail = jail.param(key, to_param_value(value));
+            }
+        }
+    }
+
+    Ok(jail)
+}
+
+/// Infer `param::Value::Int` vs `String` vs `Strings` from a raw, untyped
+/// config value. A `+=`-built list is always kept as a list (even a single
+/// entry), since that's what distinguishes `foo = bar;` from `foo += bar;`.
+fn to_param_value(value: RawValue) -> param::Value {
+    match value {
+        RawValue::Bool(b) => param::Value::Int(b as i32),
+        RawValue::Scalar(s, _line) => match s.parse::<i32>() {
+            Ok(n) => param::Value::Int(n),
+            Err(_) => param::Value::String(s),
+        },
+        RawValue::List(l) => param::Value::Strings(l.into_iter().map(|(s, _line)| s).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_simple_named_block() {
+        let jails = parse(
+            r#"
+            path = "/jails/$name";
+
+            web {
+                host.hostname = "web.example.com";
+                ip4.addr = "10.0.0.2";
+                allow.raw_sockets = 1;
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(jails.len(), 1);
+        let web = &jails[0];
+        assert_eq!(web.name, Some("web".to_string()));
+        assert_eq!(web.path, Some("/jails/web".into()));
+        assert_eq!(web.hostname, Some("web.example.com".to_string()));
+        assert_eq!(web.ips, vec!["10.0.0.2".parse::<IpAddr>().unwrap()]);
+        assert_eq!(
+            web.params.get("allow.raw_sockets"),
+            Some(&param::Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn wildcard_block_supplies_defaults() {
+        let jails = parse(
+            r#"
+            * {
+                allow.raw_sockets = 1;
+            }
+
+            web {
+                host.hostname = "web.example.com";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            jails[0].params.get("allow.raw_sockets"),
+            Some(&param::Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn explicit_value_overrides_wildcard_default() {
+        let jails = parse(
+            r#"
+            * {
+                allow.raw_sockets = 0;
+            }
+
+            web {
+                allow.raw_sockets = 1;
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            jails[0].params.get("allow.raw_sockets"),
+            Some(&param::Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn plus_equals_builds_a_list() {
+        let jails = parse(
+            r#"
+            web {
+                exec.poststart += "echo one";
+                exec.poststart += "echo two";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        let value = jails[0].params.get("exec.poststart").unwrap();
+        assert_eq!(
+            value.unpack_strings().unwrap(),
+            &vec!["echo one".to_string(), "echo two".to_string()]
+        );
+    }
+
+    #[test]
+    fn dollar_name_interpolates_jail_name() {
+        let jails = parse(
+            r#"
+            web {
+                path = "/jails/$name";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(jails[0].path, Some("/jails/web".into()));
+    }
+
+    #[test]
+    fn dollar_brace_interpolates_earlier_parameter() {
+        let jails = parse(
+            r#"
+            web {
+                devfs_ruleset = "4";
+                allow.mount.devfs = "${devfs_ruleset}";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            jails[0].params.get("allow.mount.devfs"),
+            Some(&param::Value::Int(4))
+        );
+    }
+
+    #[test]
+    fn semicolon_and_hash_inside_quotes_are_not_delimiters() {
+        let jails = parse(
+            r#"
+            web {
+                exec.start = "/bin/sh -c 'foo; bar # not a comment'";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            jails[0].params.get("exec.start"),
+            Some(&param::Value::String(
+                "/bin/sh -c 'foo; bar # not a comment'".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn brace_inside_quotes_does_not_close_the_block() {
+        let jails = parse(
+            r#"
+            web {
+                exec.start = "echo }";
+                host.hostname = "web.example.com";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            jails[0].params.get("exec.start"),
+            Some(&param::Value::String("echo }".to_string()))
+        );
+        assert_eq!(jails[0].hostname, Some("web.example.com".to_string()));
+    }
+
+    #[test]
+    fn comment_markers_inside_quotes_are_kept() {
+        let jails = parse(
+            r#"
+            web {
+                exec.start = "echo # not a comment";
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(
+            jails[0].params.get("exec.start"),
+            Some(&param::Value::String("echo # not a comment".to_string()))
+        );
+    }
+
+    #[test]
+    fn comments_and_bare_flags_are_handled() {
+        let jails = parse(
+            r#"
+            # a top-level comment
+            web {
+                /* a block comment */
+                persist;
+            }
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(jails[0].params.get("persist"), Some(&param::Value::Int(1)));
+    }
+
+    #[test]
+    fn invalid_ip_address_error_reports_its_line() {
+        let err = parse(
+            r#"
+            web {
+                host.hostname = "web.example.com";
+                ip4.addr = "not an ip";
+            }
+            "#,
+        )
+        .expect_err("should fail to parse");
+
+        match err {
+            JailError::ConfigParseError { line, .. } => assert_eq!(line, 4),
+            other => panic!("expected ConfigParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unterminated_block_error_reports_its_opening_line() {
+        let err = parse(
+            r#"
+            web {
+                persist;
+            "#,
+        )
+        .expect_err("should fail to parse");
+
+        match err {
+            JailError::ConfigParseError { line, .. } => assert_eq!(line, 2),
+            other => panic!("expected ConfigParseError, got {:?}", other),
+        }
+    }
+}
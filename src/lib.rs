@@ -17,6 +17,8 @@ pub mod process;
 #[macro_use]
 mod sys;
 
+pub mod config;
+
 pub mod param;
 
 #[macro_use]
@@ -87,6 +89,15 @@ pub enum JailError {
 
     #[fail(display = "Could not serialize value to bytes")]
     SerializeFailed,
+
+    #[fail(display = "jail.conf parse error at line {}: {}", line, msg)]
+    ConfigParseError { line: usize, msg: String },
+
+    #[fail(display = "jail_attach failed: {}", _0)]
+    AttachError(String),
+
+    #[fail(display = "failed to execute command in jail: {}", _0)]
+    ExecError(String),
 }
 
 impl JailError {
@@ -123,6 +134,28 @@ pub struct RunningJail {
     pub jid: i32,
 }
 
+/// An iterator over every running jail on the host, returned by
+/// [`RunningJail::all`](struct.RunningJail.html#method.all).
+#[cfg(target_os = "freebsd")]
+pub struct RunningJails {
+    lastjid: i32,
+}
+
+#[cfg(target_os = "freebsd")]
+impl Iterator for RunningJails {
+    type Item = RunningJail;
+
+    fn next(&mut self) -> Option<RunningJail> {
+        match sys::jail_next(self.lastjid) {
+            Ok(Some(jid)) => {
+                self.lastjid = jid;
+                Some(RunningJail::from_jid(jid))
+            }
+            _ => None,
+        }
+    }
+}
+
 /// Represents a running or stopped jail.
 #[cfg(target_os = "freebsd")]
 pub enum Jail {
@@ -205,6 +238,19 @@ impl Jail {
     }
 }
 
+/// Format `name=value` pairs the way `jail -e`/`jls -e` would, routing
+/// every value through [`param::Value::to_export_string`](param/enum.Value.html#method.to_export_string)
+/// so that [`StoppedJail::export`](struct.StoppedJail.html#method.export) and
+/// [`RunningJail::export`](struct.RunningJail.html#method.export) always
+/// agree on formatting for the same conceptual jail.
+fn format_export_pairs(pairs: &[(String, param::Value)], separator: &str) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value.to_export_string(separator)))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
 #[cfg(target_os = "freebsd")]
 impl Default for StoppedJail {
     fn default() -> StoppedJail {
@@ -364,6 +410,69 @@ impl StoppedJail {
         self.ips.push(ip);
         self
     }
+
+    /// Serialize this jail's configuration to a string of `name=value`
+    /// pairs joined by `separator`, matching the output of `jail -e`/`jls`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use jail::StoppedJail;
+    ///
+    /// let stopped = StoppedJail::new("/rescue").name("example");
+    /// let exported = stopped.export(" ").expect("could not export jail");
+    /// ```
+    pub fn export(&self, separator: &str) -> Result<String, JailError> {
+        let mut pairs: Vec<(String, param::Value)> = vec![];
+
+        if let Some(ref path) = self.path {
+            pairs.push((
+                "path".to_string(),
+                param::Value::String(path.display().to_string()),
+            ));
+        }
+
+        if let Some(ref name) = self.name {
+            pairs.push(("name".to_string(), param::Value::String(name.clone())));
+        }
+
+        if let Some(ref hostname) = self.hostname {
+            pairs.push((
+                "host.hostname".to_string(),
+                param::Value::String(hostname.clone()),
+            ));
+        }
+
+        let ip4s: Vec<net::Ipv4Addr> = self
+            .ips
+            .iter()
+            .filter_map(|ip| match ip {
+                net::IpAddr::V4(ip4) => Some(*ip4),
+                _ => None,
+            })
+            .collect();
+        if !ip4s.is_empty() {
+            pairs.push(("ip4.addr".to_string(), param::Value::Ipv4Addrs(ip4s)));
+        }
+
+        let ip6s: Vec<net::Ipv6Addr> = self
+            .ips
+            .iter()
+            .filter_map(|ip| match ip {
+                net::IpAddr::V6(ip6) => Some(*ip6),
+                _ => None,
+            })
+            .collect();
+        if !ip6s.is_empty() {
+            pairs.push(("ip6.addr".to_string(), param::Value::Ipv6Addrs(ip6s)));
+        }
+
+        for (name, value) in &self.params {
+            pairs.push((name.clone(), value.clone()));
+        }
+
+        Ok(format_export_pairs(&pairs, separator))
+    }
 }
 
 /// Represent a running jail.
@@ -391,6 +500,40 @@ impl RunningJail {
         RunningJail { jid }
     }
 
+    /// Return an iterator over every running jail on the host.
+    ///
+    /// Internally this repeatedly calls `jail_get(2)` with the `lastjid`
+    /// parameter, the same technique `jls(8)` uses to enumerate jails,
+    /// feeding each returned `jid` back in as the next `lastjid` until the
+    /// kernel reports there are none left.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use jail::RunningJail;
+    ///
+    /// for jail in RunningJail::all() {
+    ///     println!("{}", jail.name().unwrap());
+    /// }
+    /// ```
+    pub fn all() -> RunningJails {
+        RunningJails { lastjid: 0 }
+    }
+
+    /// Collect [`all`](#method.all) into a `Vec`, mirroring what `jls(8)`
+    /// prints.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use jail::RunningJail;
+    ///
+    /// let jails = RunningJail::jails();
+    /// ```
+    pub fn jails() -> Vec<RunningJail> {
+        RunningJail::all().collect()
+    }
+
     /// Create a [RunningJail](struct.RunningJail.html) given the jail `name`.
     ///
     /// The `jid` will be internally resolved using
@@ -551,4 +694,109 @@ impl RunningJail {
     pub fn kill(self: RunningJail) -> Result<(), JailError> {
         sys::jail_remove(self.jid).and_then(|_| Ok(()))
     }
+
+    /// Attach the calling process to this jail.
+    ///
+    /// This calls `jail_attach(2)`, moving the current process (and its
+    /// children) into the jail. Most callers will want
+    /// [`command`](#method.command) instead, which does this in a forked
+    /// child so the calling process stays outside the jail.
+    pub fn attach(self: &Self) -> Result<(), JailError> {
+        sys::jail_attach(self.jid)
+    }
+
+    /// Build a command to run inside this jail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use jail::StoppedJail;
+    /// # let running = StoppedJail::new("/rescue")
+    /// #     .start().unwrap();
+    /// #
+    /// let status = running.command("/bin/sh")
+    ///     .arg("-c")
+    ///     .arg("echo hello")
+    ///     .spawn()
+    ///     .expect("could not spawn command")
+    ///     .wait()
+    ///     .expect("could not wait for command");
+    /// # running.kill();
+    /// ```
+    pub fn command<S: Into<String>>(self: &Self, program: S) -> process::JailCommand {
+        process::JailCommand::new(self.jid, program)
+    }
+
+    /// Serialize this jail's complete configuration to a string of
+    /// `name=value` pairs joined by `separator`, matching the output of
+    /// `jail -e`/`jls`.
+    ///
+    /// Every parameter known to the kernel (per the `security.jail.param`
+    /// sysctl tree) is read and formatted; parameters this jail doesn't
+    /// have set, or can't be read, are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use jail::StoppedJail;
+    /// # let running = StoppedJail::new("/rescue")
+    /// #     .start().unwrap();
+    /// let exported = running.export(" ").expect("could not export jail");
+    /// # running.kill();
+    /// ```
+    pub fn export(self: &Self, separator: &str) -> Result<String, JailError> {
+        let mut pairs = vec![];
+
+        for name in param::all_param_names()? {
+            if let Ok(value) = self.param(&name) {
+                pairs.push((name, value));
+            }
+        }
+
+        Ok(format_export_pairs(&pairs, separator))
+    }
+
+    /// Snapshot this running jail's configuration into a
+    /// [`StoppedJail`](struct.StoppedJail.html) that can later be
+    /// `.start()`ed again.
+    ///
+    /// `path`, `name`, `host.hostname` and `ip4.addr`/`ip6.addr` are
+    /// captured into their dedicated `StoppedJail` fields; every other
+    /// parameter that is actually settable via `jail_set(2)` is captured
+    /// into `params`. Read-only parameters (`jid`, `host.hostid`, etc.) are
+    /// skipped, since feeding them back to `jail_set(2)` would fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use jail::StoppedJail;
+    /// # let running = StoppedJail::new("/rescue")
+    /// #     .name("testjail_defrost")
+    /// #     .start().unwrap();
+    /// let frozen = running.defrost().expect("could not defrost jail");
+    /// # running.kill();
+    /// ```
+    pub fn defrost(self: &Self) -> Result<StoppedJail, JailError> {
+        let path = self.param("path")?.unpack_string()?;
+
+        let mut jail = StoppedJail::new(path)
+            .name(self.name()?)
+            .hostname(self.hostname()?);
+
+        for ip in self.ips()? {
+            jail = jail.ip(ip);
+        }
+
+        for name in param::all_param_names()? {
+            if !param::is_settable_param(&name) {
+                continue;
+            }
+
+            if let Ok(value) = self.param(&name) {
+                jail = jail.param(name, value);
+            }
+        }
+
+        Ok(jail)
+    }
 }
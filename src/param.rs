@@ -0,0 +1,544 @@
+//! Reading and writing of jail parameters.
+//!
+//! Jail parameters are typed, but the kernel only exposes that typing via
+//! the `security.jail.param.*` sysctl tree, so [`get`](fn.get.html)
+//! consults that tree to figure out how to decode a given parameter name
+//! before talking to `jail_get(2)`. [`set`](fn.set.html) encodes the value
+//! according to the `Value` variant it is given before calling
+//! `jail_set(2)`.
+
+use std::ffi::CString;
+use std::net;
+use std::str::FromStr;
+
+use libc::iovec;
+use sysctl::{Ctl, CtlIter, CtlType, CtlValue, Sysctl};
+
+use sys;
+use JailError;
+
+/// The three-valued "jailsys" parameter type used by `ip4`, `ip6`, `host`,
+/// `vnet`, `sysvmsg`, `sysvsem` and `sysvshm`, which a plain boolean can't
+/// represent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JailSys {
+    Disable,
+    New,
+    Inherit,
+}
+
+impl JailSys {
+    fn from_int(v: i32) -> Result<JailSys, JailError> {
+        match v {
+            0 => Ok(JailSys::Disable),
+            1 => Ok(JailSys::New),
+            2 => Ok(JailSys::Inherit),
+            _ => Err(JailError::ParameterUnpackError),
+        }
+    }
+
+    fn to_int(self) -> i32 {
+        match self {
+            JailSys::Disable => 0,
+            JailSys::New => 1,
+            JailSys::Inherit => 2,
+        }
+    }
+}
+
+/// Parameters whose kernel-reported sysctl type is a plain `Int`, but whose
+/// actual values are a [`JailSys`](enum.JailSys.html) tri-state rather than
+/// a boolean or count.
+const JAILSYS_PARAMS: &[&str] = &[
+    "vnet", "ip4", "ip6", "host", "sysvmsg", "sysvsem", "sysvshm",
+];
+
+fn is_jailsys_param(name: &str) -> bool {
+    JAILSYS_PARAMS.contains(&name)
+}
+
+/// Parameters the kernel reports but that can't be fed back into
+/// `jail_set(2)`, either because they're purely informational (`jid`,
+/// `dying`, `parent`) or only make sense at creation time (`path`, `name`,
+/// `ip4.addr`, `ip6.addr`, which `StoppedJail` tracks separately).
+const READONLY_PARAMS: &[&str] = &[
+    "jid",
+    "parent",
+    "dying",
+    "host.hostid",
+    "osrelease",
+    "osreldate",
+    "path",
+    "name",
+    "host.hostname",
+    "ip4.addr",
+    "ip6.addr",
+];
+
+/// Whether `name` can be round-tripped through `StoppedJail::param` /
+/// `jail_set(2)`. See [`RunningJail::defrost`](../struct.RunningJail.html#method.defrost).
+pub fn is_settable_param(name: &str) -> bool {
+    !READONLY_PARAMS.contains(&name)
+}
+
+/// A typed jail parameter value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Value {
+    Int(i32),
+    Ulong(u64),
+    S64(i64),
+    U64(u64),
+    String(String),
+    /// A parameter that takes more than one string value, such as a
+    /// repeatable `exec.*` hook. Encoded on the wire as one `iovec` pair
+    /// per entry, all sharing the parameter name.
+    Strings(Vec<String>),
+    Ipv4Addrs(Vec<net::Ipv4Addr>),
+    Ipv6Addrs(Vec<net::Ipv6Addr>),
+    JailSys(JailSys),
+}
+
+impl Value {
+    /// Unpack a [`Value::String`](enum.Value.html#variant.String), failing
+    /// for any other variant.
+    pub fn unpack_string(self) -> Result<String, JailError> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(JailError::ParameterUnpackError),
+        }
+    }
+
+    /// Unpack a [`Value::Strings`](enum.Value.html#variant.Strings), failing
+    /// for any other variant.
+    pub fn unpack_strings(&self) -> Result<&Vec<String>, JailError> {
+        match self {
+            Value::Strings(s) => Ok(s),
+            _ => Err(JailError::ParameterUnpackError),
+        }
+    }
+
+    /// Unpack a [`Value::Ipv4Addrs`](enum.Value.html#variant.Ipv4Addrs),
+    /// failing for any other variant.
+    pub fn unpack_ipv4(&self) -> Result<&Vec<net::Ipv4Addr>, JailError> {
+        match self {
+            Value::Ipv4Addrs(ips) => Ok(ips),
+            _ => Err(JailError::ParameterUnpackError),
+        }
+    }
+
+    /// Unpack a [`Value::Ipv6Addrs`](enum.Value.html#variant.Ipv6Addrs),
+    /// failing for any other variant.
+    pub fn unpack_ipv6(&self) -> Result<&Vec<net::Ipv6Addr>, JailError> {
+        match self {
+            Value::Ipv6Addrs(ips) => Ok(ips),
+            _ => Err(JailError::ParameterUnpackError),
+        }
+    }
+
+    /// Unpack a [`Value::JailSys`](enum.Value.html#variant.JailSys), failing
+    /// for any other variant.
+    pub fn unpack_jailsys(&self) -> Result<JailSys, JailError> {
+        match self {
+            Value::JailSys(v) => Ok(*v),
+            _ => Err(JailError::ParameterUnpackError),
+        }
+    }
+
+    /// Render the value the way `jail -e`/`jls -e` would: list-valued
+    /// parameters are comma-joined, and strings are quoted when they
+    /// contain `separator`.
+    pub fn to_export_string(&self, separator: &str) -> String {
+        match self {
+            Value::Int(v) => v.to_string(),
+            Value::Ulong(v) => v.to_string(),
+            Value::S64(v) => v.to_string(),
+            Value::U64(v) => v.to_string(),
+            Value::String(s) => {
+                if s.contains(separator) {
+                    format!("\"{}\"", s)
+                } else {
+                    s.clone()
+                }
+            }
+            Value::Strings(list) => list.join(","),
+            Value::Ipv4Addrs(ips) => ips
+                .iter()
+                .map(net::Ipv4Addr::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            Value::Ipv6Addrs(ips) => ips
+                .iter()
+                .map(net::Ipv6Addr::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            Value::JailSys(JailSys::Disable) => "disable".to_string(),
+            Value::JailSys(JailSys::New) => "new".to_string(),
+            Value::JailSys(JailSys::Inherit) => "inherit".to_string(),
+        }
+    }
+}
+
+/// The kernel exposes array-typed parameters (e.g. `ip4.addr`) under
+/// `security.jail.param` as one sysctl node per array slot, suffixed with
+/// a trailing `.<index>` (e.g. `ip4.addr.0`, `ip4.addr.1`). Strip that
+/// suffix so a parameter's base name is computed the same way whether
+/// we're enumerating the tree or looking up a single name's type.
+///
+/// Only a trailing `.<digits>` segment is stripped — a bare trailing digit
+/// with no preceding `.` is part of the name itself (e.g. `ip4`, `ip6`),
+/// not an array index.
+fn normalize_param_name(name: &str) -> String {
+    match name.rfind('.') {
+        Some(dot)
+            if !name[dot + 1..].is_empty() && name[dot + 1..].chars().all(|c| c.is_numeric()) =>
+        {
+            name[..dot].to_string()
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Enumerate every known jail parameter name from the `security.jail.param`
+/// sysctl tree, the same tree [`get`](fn.get.html)/[`set`](fn.set.html)
+/// already consult to determine a parameter's type.
+pub fn all_param_names() -> Result<Vec<String>, JailError> {
+    let root = Ctl::new("security.jail.param").map_err(|e| JailError::ParameterTypeError(e))?;
+
+    let prefix = "security.jail.param.";
+
+    let mut names: Vec<String> = CtlIter::below(root)
+        .filter_map(|ctl| ctl.ok())
+        .filter_map(|ctl| ctl.name().ok())
+        .filter_map(|name| name.strip_prefix(prefix).map(normalize_param_name))
+        .collect();
+
+    // Array-typed parameters contribute one sysctl node per slot, so the
+    // same base name can appear more than once above.
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+/// Look up the kernel-reported type of a jail parameter via
+/// `security.jail.param.<name>`.
+fn param_type(name: &str) -> Result<CtlType, JailError> {
+    let oid = format!("security.jail.param.{}", normalize_param_name(name));
+
+    Ctl::new(&oid)
+        .map_err(|_| JailError::NoSuchParameter(name.to_string()))?
+        .value_type()
+        .map_err(JailError::ParameterTypeError)
+}
+
+/// Get the value of jail parameter `name` for the jail identified by `jid`.
+pub fn get(jid: i32, name: &str) -> Result<Value, JailError> {
+    if is_jailsys_param(name) {
+        return get_int(jid, name)
+            .and_then(JailSys::from_int)
+            .map(Value::JailSys);
+    }
+
+    match param_type(name)? {
+        CtlType::Int => get_int(jid, name).map(Value::Int),
+        CtlType::S64 => get_s64(jid, name).map(Value::S64),
+        CtlType::U64 => get_u64(jid, name).map(Value::U64),
+        CtlType::Ulong => get_ulong(jid, name).map(Value::Ulong),
+        CtlType::String => get_string(jid, name).map(Value::String),
+        CtlType::Struct => get_struct(jid, name),
+        other => Err(JailError::ParameterTypeUnsupported(other)),
+    }
+}
+
+/// Set jail parameter `name` to `value` for the jail identified by `jid`.
+pub fn set(jid: i32, name: &str, value: Value) -> Result<(), JailError> {
+    let cname = CString::new(name).map_err(|_| JailError::SerializeFailed)?;
+
+    let mut name_iov = iovec {
+        iov_base: cname.as_ptr() as *mut _,
+        iov_len: cname.as_bytes_with_nul().len(),
+    };
+
+    let mut jid_name = CString::new("jid").unwrap();
+    let mut jid_val = jid;
+
+    let mut iovecs = vec![
+        iovec {
+            iov_base: jid_name.as_ptr() as *mut _,
+            iov_len: jid_name.as_bytes_with_nul().len(),
+        },
+        iovec {
+            iov_base: &mut jid_val as *mut _ as *mut _,
+            iov_len: ::std::mem::size_of::<i32>(),
+        },
+    ];
+
+    // Backing storage for the value's iovec(s). This has to outlive the
+    // `sys::jail_set` call below, so it's bound out here rather than inside
+    // the `match` (where it would drop, and the iovecs would dangle, before
+    // the syscall runs) — there's no need to `mem::forget` and leak it, just
+    // to keep it alive that long.
+    let mut int_buf: i32 = 0;
+    let mut cstr_buf: Option<CString> = None;
+    let mut cstrs_buf: Option<Vec<CString>> = None;
+    let mut ipv4_buf: Option<Vec<net::Ipv4Addr>> = None;
+    let mut ipv6_buf: Option<Vec<net::Ipv6Addr>> = None;
+
+    match value {
+        Value::Int(v) => {
+            int_buf = v;
+            iovecs.push(name_iov);
+            iovecs.push(iovec {
+                iov_base: &mut int_buf as *mut _ as *mut _,
+                iov_len: ::std::mem::size_of::<i32>(),
+            });
+        }
+        Value::JailSys(jailsys) => {
+            int_buf = jailsys.to_int();
+            iovecs.push(name_iov);
+            iovecs.push(iovec {
+                iov_base: &mut int_buf as *mut _ as *mut _,
+                iov_len: ::std::mem::size_of::<i32>(),
+            });
+        }
+        Value::String(s) => {
+            let cstr = CString::new(s).map_err(|_| JailError::SerializeFailed)?;
+            iovecs.push(name_iov);
+            iovecs.push(iovec {
+                iov_base: cstr.as_ptr() as *mut _,
+                iov_len: cstr.as_bytes_with_nul().len(),
+            });
+            cstr_buf = Some(cstr);
+        }
+        Value::Strings(list) => {
+            let cstrs = list
+                .into_iter()
+                .map(|s| CString::new(s).map_err(|_| JailError::SerializeFailed))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for cstr in &cstrs {
+                iovecs.push(name_iov);
+                iovecs.push(iovec {
+                    iov_base: cstr.as_ptr() as *mut _,
+                    iov_len: cstr.as_bytes_with_nul().len(),
+                });
+            }
+
+            cstrs_buf = Some(cstrs);
+        }
+        Value::Ipv4Addrs(mut ips) => {
+            iovecs.push(name_iov);
+            iovecs.push(iovec {
+                iov_base: ips.as_mut_ptr() as *mut _,
+                iov_len: ips.len() * ::std::mem::size_of::<net::Ipv4Addr>(),
+            });
+            ipv4_buf = Some(ips);
+        }
+        Value::Ipv6Addrs(mut ips) => {
+            iovecs.push(name_iov);
+            iovecs.push(iovec {
+                iov_base: ips.as_mut_ptr() as *mut _,
+                iov_len: ips.len() * ::std::mem::size_of::<net::Ipv6Addr>(),
+            });
+            ipv6_buf = Some(ips);
+        }
+        _ => return Err(JailError::SerializeFailed),
+    };
+
+    let _ = &mut name_iov;
+
+    let result = sys::jail_set(&mut iovecs, 0).map(|_| ());
+
+    // `int_buf`/`cstr_buf`/`cstrs_buf`/`ipv4_buf`/`ipv6_buf` are dropped here, once
+    // the syscall that reads their backing memory has returned.
+    result
+}
+
+fn get_int(jid: i32, name: &str) -> Result<i32, JailError> {
+    let mut value: i32 = 0;
+    get_raw(
+        jid,
+        name,
+        &mut value as *mut _ as *mut _,
+        ::std::mem::size_of::<i32>(),
+    )?;
+    Ok(value)
+}
+
+fn get_ulong(jid: i32, name: &str) -> Result<u64, JailError> {
+    let mut value: u64 = 0;
+    get_raw(
+        jid,
+        name,
+        &mut value as *mut _ as *mut _,
+        ::std::mem::size_of::<u64>(),
+    )?;
+    Ok(value)
+}
+
+fn get_s64(jid: i32, name: &str) -> Result<i64, JailError> {
+    let mut value: i64 = 0;
+    get_raw(
+        jid,
+        name,
+        &mut value as *mut _ as *mut _,
+        ::std::mem::size_of::<i64>(),
+    )?;
+    Ok(value)
+}
+
+fn get_u64(jid: i32, name: &str) -> Result<u64, JailError> {
+    let mut value: u64 = 0;
+    get_raw(
+        jid,
+        name,
+        &mut value as *mut _ as *mut _,
+        ::std::mem::size_of::<u64>(),
+    )?;
+    Ok(value)
+}
+
+fn get_string(jid: i32, name: &str) -> Result<String, JailError> {
+    let oid = format!("security.jail.param.{}", name);
+    let len = match Ctl::new(&oid).ok().and_then(|c| c.value().ok()) {
+        Some(CtlValue::String(s)) => usize::from_str(s.trim_start_matches('A'))
+            .map_err(|_| JailError::ParameterLengthNaN(s))?,
+        _ => 256,
+    };
+
+    let mut buf = vec![0u8; len];
+    get_raw(jid, name, buf.as_mut_ptr() as *mut _, len)?;
+
+    let nul = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    buf.truncate(nul);
+
+    String::from_utf8(buf).map_err(|_| JailError::ParameterUnpackError)
+}
+
+/// Structured values (e.g. `ip4.addr`) are always read as arrays of the
+/// matching address type; the specific parameter name tells us which.
+fn get_struct(jid: i32, name: &str) -> Result<Value, JailError> {
+    if name == "ip4.addr" {
+        let max = get_max_af_ips("ip4")?;
+        let mut buf = vec![net::Ipv4Addr::from(0u32); max];
+        get_raw(
+            jid,
+            name,
+            buf.as_mut_ptr() as *mut _,
+            max * ::std::mem::size_of::<net::Ipv4Addr>(),
+        )?;
+        return Ok(Value::Ipv4Addrs(buf));
+    }
+
+    if name == "ip6.addr" {
+        let max = get_max_af_ips("ip6")?;
+        let mut buf = vec![net::Ipv6Addr::from([0u8; 16]); max];
+        get_raw(
+            jid,
+            name,
+            buf.as_mut_ptr() as *mut _,
+            max * ::std::mem::size_of::<net::Ipv6Addr>(),
+        )?;
+        return Ok(Value::Ipv6Addrs(buf));
+    }
+
+    Err(JailError::ParameterTypeUnsupported(CtlType::Struct))
+}
+
+fn get_max_af_ips(af: &str) -> Result<usize, JailError> {
+    let oid = format!(
+        "security.jail.{}",
+        match af {
+            "ip4" => "jail_max_af_ips",
+            _ => "jail_max_af_ips",
+        }
+    );
+
+    match Ctl::new(&oid).and_then(|c| c.value()) {
+        Ok(CtlValue::Int(n)) => Ok(n as usize),
+        Ok(CtlValue::Uint(n)) => Ok(n as usize),
+        Err(e) => Err(JailError::JailMaxAfIpsFailed(e)),
+        _ => Ok(1),
+    }
+}
+
+fn get_raw(
+    jid: i32,
+    name: &str,
+    out: *mut ::std::os::raw::c_void,
+    len: usize,
+) -> Result<(), JailError> {
+    let cname = CString::new(name).map_err(|_| JailError::SerializeFailed)?;
+    let mut jid_name = CString::new("jid").unwrap();
+    let mut jid_val = jid;
+
+    let mut iovecs = vec![
+        iovec {
+            iov_base: jid_name.as_ptr() as *mut _,
+            iov_len: jid_name.as_bytes_with_nul().len(),
+        },
+        iovec {
+            iov_base: &mut jid_val as *mut _ as *mut _,
+            iov_len: ::std::mem::size_of::<i32>(),
+        },
+        iovec {
+            iov_base: cname.as_ptr() as *mut _,
+            iov_len: cname.as_bytes_with_nul().len(),
+        },
+        iovec {
+            iov_base: out,
+            iov_len: len,
+        },
+    ];
+
+    sys::jail_get(&mut iovecs, 0).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_param_name_is_a_no_op_for_plain_names() {
+        assert_eq!(normalize_param_name("ip4.addr"), "ip4.addr");
+        assert_eq!(
+            normalize_param_name("allow.raw_sockets"),
+            "allow.raw_sockets"
+        );
+    }
+
+    #[test]
+    fn normalize_param_name_strips_trailing_array_slot() {
+        // `security.jail.param` reports one node per array slot for
+        // struct-typed parameters, e.g. `ip4.addr.0`, `ip4.addr.1`.
+        assert_eq!(normalize_param_name("ip4.addr.0"), "ip4.addr");
+        assert_eq!(normalize_param_name("ip4.addr.12"), "ip4.addr");
+    }
+
+    #[test]
+    fn normalize_param_name_leaves_bare_trailing_digits_alone() {
+        // `ip4`/`ip6` end in a digit that is part of the name, not an
+        // array-index suffix, so they must not collapse into one another.
+        assert_eq!(normalize_param_name("ip4"), "ip4");
+        assert_eq!(normalize_param_name("ip6"), "ip6");
+    }
+
+    #[test]
+    fn to_export_string_joins_list_values() {
+        let value = Value::Strings(vec!["one".to_string(), "two".to_string()]);
+        assert_eq!(value.to_export_string(" "), "one,two");
+    }
+
+    #[test]
+    fn ip4_and_ip6_jailsys_params_are_settable() {
+        // `RunningJail::defrost` relies on `is_settable_param` to
+        // decide which parameters to carry over; the `ip4`/`ip6` jailsys
+        // tri-states (vnet-style inheritance mode) must round-trip, unlike
+        // the read-only `ip4.addr`/`ip6.addr` address lists, which
+        // `StoppedJail` tracks separately.
+        assert!(is_settable_param("ip4"));
+        assert!(is_settable_param("ip6"));
+        assert!(!is_settable_param("ip4.addr"));
+        assert!(!is_settable_param("ip6.addr"));
+    }
+}
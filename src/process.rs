@@ -0,0 +1,364 @@
+//! Helpers for running processes inside a jail.
+//!
+//! [`JailCommand`](struct.JailCommand.html) mirrors `std::process::Command`:
+//! build one up with the program, arguments, environment and working
+//! directory you want, then `.spawn()` or `.output()` it. Under the hood
+//! this forks, calls `jail_attach(2)` in the child, and `execve`s the
+//! requested program from inside the jail.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::PathBuf;
+
+use libc;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::unistd::{self, ForkResult};
+
+use sys;
+use JailError;
+
+/// The PID of a process, as seen from the host.
+pub type Pid = libc::pid_t;
+
+/// The exit status of a process run with [`JailCommand`](struct.JailCommand.html).
+///
+/// Unlike a bare exit code, this distinguishes a process that ran to
+/// completion from one that was killed by a signal, mirroring
+/// `std::process::ExitStatus`.
+#[derive(Clone, Copy, Debug)]
+pub enum ExitStatus {
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl ExitStatus {
+    /// Whether the process exited normally with status code `0`.
+    pub fn success(&self) -> bool {
+        match self {
+            ExitStatus::Exited(0) => true,
+            _ => false,
+        }
+    }
+
+    /// The exit code, or `None` if the process was killed by a signal.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ExitStatus::Exited(code) => Some(*code),
+            ExitStatus::Signaled(_) => None,
+        }
+    }
+
+    /// The signal that killed the process, or `None` if it exited normally.
+    pub fn signal(&self) -> Option<i32> {
+        match self {
+            ExitStatus::Exited(_) => None,
+            ExitStatus::Signaled(sig) => Some(*sig),
+        }
+    }
+}
+
+/// A spawned, but not yet waited-on, child process running inside a jail.
+pub struct JailChild {
+    pub pid: Pid,
+    stdout: Option<File>,
+    stderr: Option<File>,
+}
+
+impl JailChild {
+    /// Block until the child exits, returning its exit status.
+    pub fn wait(&mut self) -> Result<ExitStatus, JailError> {
+        let mut status: libc::c_int = 0;
+        let ret = unsafe { libc::waitpid(self.pid, &mut status, 0) };
+
+        if ret < 0 {
+            return Err(JailError::ExecError(
+                ::std::io::Error::last_os_error().to_string(),
+            ));
+        }
+
+        if libc::WIFEXITED(status) {
+            Ok(ExitStatus::Exited(libc::WEXITSTATUS(status)))
+        } else if libc::WIFSIGNALED(status) {
+            Ok(ExitStatus::Signaled(libc::WTERMSIG(status)))
+        } else {
+            Err(JailError::ExecError(format!(
+                "unrecognized wait status: {}",
+                status
+            )))
+        }
+    }
+}
+
+/// The captured output of a [`JailCommand`](struct.JailCommand.html) run to
+/// completion via [`output`](struct.JailCommand.html#method.output).
+pub struct JailOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// A builder for a process to run inside a jail, analogous to
+/// `std::process::Command`.
+pub struct JailCommand {
+    jid: i32,
+    program: String,
+    args: Vec<String>,
+    env: HashMap<String, String>,
+    current_dir: Option<PathBuf>,
+    preserve_fds: Vec<RawFd>,
+}
+
+impl JailCommand {
+    /// Start building a command that will run `program` inside the jail
+    /// identified by `jid`.
+    pub fn new<S: Into<String>>(jid: i32, program: S) -> JailCommand {
+        JailCommand {
+            jid,
+            program: program.into(),
+            args: vec![],
+            env: HashMap::new(),
+            current_dir: None,
+            preserve_fds: vec![],
+        }
+    }
+
+    /// Add an argument to pass to the program.
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add multiple arguments to pass to the program.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set an environment variable for the program.
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, val: V) -> Self {
+        self.env.insert(key.into(), val.into());
+        self
+    }
+
+    /// Set multiple environment variables for the program.
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        for (k, v) in vars {
+            self.env.insert(k.into(), v.into());
+        }
+        self
+    }
+
+    /// Set the working directory of the program, relative to the jail's
+    /// root.
+    pub fn current_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Keep the given file descriptors open (and free of `FD_CLOEXEC`)
+    /// across the `execve`, in addition to stdin/stdout/stderr. Every other
+    /// open file descriptor in the child is marked `FD_CLOEXEC` and so is
+    /// closed by the kernel at `execve` time.
+    pub fn preserve_fds(mut self, fds: Vec<RawFd>) -> Self {
+        self.preserve_fds = fds;
+        self
+    }
+
+    /// Fork, attach to the jail in the child, and `execve` the program.
+    ///
+    /// Returns a [`JailChild`](struct.JailChild.html) handle to the running
+    /// process in the parent.
+    pub fn spawn(&self) -> Result<JailChild, JailError> {
+        self.spawn_internal(false)
+    }
+
+    /// Run the program to completion, capturing its output.
+    pub fn output(&self) -> Result<JailOutput, JailError> {
+        let mut child = self.spawn_internal(true)?;
+
+        let mut stdout = vec![];
+        if let Some(ref mut out) = child.stdout {
+            out.read_to_end(&mut stdout)
+                .map_err(|e| JailError::ExecError(e.to_string()))?;
+        }
+
+        let mut stderr = vec![];
+        if let Some(ref mut err) = child.stderr {
+            err.read_to_end(&mut stderr)
+                .map_err(|e| JailError::ExecError(e.to_string()))?;
+        }
+
+        let status = child.wait()?;
+
+        Ok(JailOutput {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Shared implementation of `spawn`/`output`.
+    ///
+    /// The `CString`s for the program, its arguments and its environment,
+    /// and the `/dev/fd` scan used to decide which file descriptors to
+    /// close, are built here, in the parent, before `fork()`. The child
+    /// runs `close`/`dup2`/`fcntl`/`jail_attach`/`chdir`/`execve` over
+    /// those prebuilt values; its error paths format the error as a
+    /// `String` and `eprintln!` it before `_exit`, so the post-fork,
+    /// pre-exec window is not guaranteed to be allocation-free.
+    fn spawn_internal(&self, capture: bool) -> Result<JailChild, JailError> {
+        let program =
+            CString::new(self.program.as_str()).map_err(|_| JailError::SerializeFailed)?;
+
+        let mut argv: Vec<CString> = vec![program.clone()];
+        for arg in &self.args {
+            argv.push(CString::new(arg.as_str()).map_err(|_| JailError::SerializeFailed)?);
+        }
+
+        let envp: Vec<CString> = self
+            .env
+            .iter()
+            .map(|(k, v)| {
+                CString::new(format!("{}={}", k, v)).map_err(|_| JailError::SerializeFailed)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let stdout_pipe = if capture {
+            Some(unistd::pipe().map_err(|e| JailError::ExecError(e.to_string()))?)
+        } else {
+            None
+        };
+        let stderr_pipe = if capture {
+            Some(unistd::pipe().map_err(|e| JailError::ExecError(e.to_string()))?)
+        } else {
+            None
+        };
+
+        // Every fd open at this point will also be open in the forked
+        // child (fork duplicates the fd table), so it's safe to decide
+        // here, in the parent, which of them the child should close.
+        let close_fds = fds_to_close(&self.preserve_fds);
+
+        match unistd::fork().map_err(|e| JailError::ExecError(e.to_string()))? {
+            ForkResult::Parent { child } => {
+                let stdout = match stdout_pipe {
+                    Some((read_fd, write_fd)) => {
+                        let _ = unistd::close(write_fd);
+                        Some(unsafe { File::from_raw_fd(read_fd) })
+                    }
+                    None => None,
+                };
+
+                let stderr = match stderr_pipe {
+                    Some((read_fd, write_fd)) => {
+                        let _ = unistd::close(write_fd);
+                        Some(unsafe { File::from_raw_fd(read_fd) })
+                    }
+                    None => None,
+                };
+
+                Ok(JailChild {
+                    pid: child,
+                    stdout,
+                    stderr,
+                })
+            }
+            ForkResult::Child => {
+                if let Err(e) =
+                    self.exec_in_child(&program, &argv, &envp, stdout_pipe, stderr_pipe, &close_fds)
+                {
+                    eprintln!("jail: failed to exec {}: {}", self.program, e);
+                    unsafe { libc::_exit(127) };
+                }
+                unreachable!("execve returned without error");
+            }
+        }
+    }
+
+    /// Only ever runs in the forked child: wires up the captured
+    /// stdout/stderr pipes (if any), marks every fd in `close_fds`
+    /// close-on-exec, clears `FD_CLOEXEC` on `preserve_fds` (so they
+    /// actually survive the `execve`), attaches to the jail, chdirs, and
+    /// `execve()`s the already-built argv/envp. Does not return on
+    /// success.
+    ///
+    /// `close_fds` is precomputed in the parent by [`fds_to_close`]; only
+    /// `fcntl` calls over that already-built list happen here, so the
+    /// `/dev/fd` scan does not run between `fork()` and `execve()`. A
+    /// failing call's error is turned into a `String` here.
+    fn exec_in_child(
+        &self,
+        program: &CString,
+        argv: &[CString],
+        envp: &[CString],
+        stdout_pipe: Option<(RawFd, RawFd)>,
+        stderr_pipe: Option<(RawFd, RawFd)>,
+        close_fds: &[RawFd],
+    ) -> Result<(), JailError> {
+        if let Some((read_fd, write_fd)) = stdout_pipe {
+            let _ = unistd::close(read_fd);
+            unistd::dup2(write_fd, libc::STDOUT_FILENO)
+                .map_err(|e| JailError::ExecError(e.to_string()))?;
+            let _ = unistd::close(write_fd);
+        }
+
+        if let Some((read_fd, write_fd)) = stderr_pipe {
+            let _ = unistd::close(read_fd);
+            unistd::dup2(write_fd, libc::STDERR_FILENO)
+                .map_err(|e| JailError::ExecError(e.to_string()))?;
+            let _ = unistd::close(write_fd);
+        }
+
+        for &fd in close_fds {
+            let _ = fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC));
+        }
+
+        for &fd in &self.preserve_fds {
+            let _ = fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()));
+        }
+
+        sys::jail_attach(self.jid)?;
+
+        if let Some(ref dir) = self.current_dir {
+            unistd::chdir(dir).map_err(|e| JailError::ExecError(e.to_string()))?;
+        }
+
+        unistd::execve(program, argv, envp).map_err(|e| JailError::ExecError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Enumerate every currently-open file descriptor (via `/dev/fd`) other
+/// than stdin/stdout/stderr and `preserve`, so the caller can mark exactly
+/// those close-on-exec in the child without doing the `/dev/fd` scan (and
+/// its `String`/`Vec` allocation) after `fork()`.
+fn fds_to_close(preserve: &[RawFd]) -> Vec<RawFd> {
+    let entries = match fs::read_dir("/dev/fd") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str().and_then(|s| s.parse().ok()))
+        .filter(|fd: &RawFd| {
+            *fd != libc::STDIN_FILENO
+                && *fd != libc::STDOUT_FILENO
+                && *fd != libc::STDERR_FILENO
+                && !preserve.contains(fd)
+        })
+        .collect()
+}
@@ -0,0 +1,194 @@
+//! Thin wrappers around the raw `jail_create(2)`, `jail_get(2)`,
+//! `jail_set(2)` and friends libc calls.
+//!
+//! These are kept separate from the rest of the crate so that the
+//! `iovec`-juggling required by the syscalls doesn't leak into code that
+//! just wants to read or write a parameter.
+
+use libc::iovec;
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::ptr;
+
+use JailError;
+
+/// Build a single `iovec` pointing at the bytes of `$name` (`as_ptr()`,
+/// `len()`). The iovec does not keep its buffer alive; the caller must
+/// hold the backing `CString`/byte buffer until the syscall has returned.
+///
+/// This mirrors the `jiov!`-style helpers used throughout the FreeBSD
+/// `jail(8)` userland tools: every parameter is represented as a pair of
+/// iovecs, one for the NUL-terminated name and one for the raw value.
+macro_rules! iov {
+    ($name:expr) => {
+        ::libc::iovec {
+            iov_base: $name.as_ptr() as *mut ::std::os::raw::c_void,
+            iov_len: $name.len(),
+        }
+    };
+}
+
+/// Create a new jail rooted at `path`, returning the new `jid`.
+pub fn jail_create(
+    path: &::std::path::Path,
+    name: Option<&str>,
+    hostname: Option<&str>,
+) -> Result<i32, JailError> {
+    let path = CString::new(path.to_string_lossy().as_bytes()).map_err(|_| JailError::SerializeFailed)?;
+
+    let mut iovecs: Vec<iovec> = vec![
+        iov!(b"path\0"),
+        iov!(path.as_bytes_with_nul()),
+        iov!(b"jid\0"),
+    ];
+
+    let mut jid: c_int = 0;
+    iovecs.push(iovec {
+        iov_base: &mut jid as *mut _ as *mut ::std::os::raw::c_void,
+        iov_len: ::std::mem::size_of::<c_int>(),
+    });
+
+    let name = name.map(|n| CString::new(n).unwrap());
+    if let Some(ref name) = name {
+        iovecs.push(iov!(b"name\0"));
+        iovecs.push(iov!(name.as_bytes_with_nul()));
+    }
+
+    let hostname = hostname.map(|h| CString::new(h).unwrap());
+    if let Some(ref hostname) = hostname {
+        iovecs.push(iov!(b"host.hostname\0"));
+        iovecs.push(iov!(hostname.as_bytes_with_nul()));
+    }
+
+    let jid = unsafe {
+        ::libc::jail_set(
+            iovecs.as_mut_ptr(),
+            iovecs.len() as u32,
+            ::libc::JAIL_CREATE,
+        )
+    };
+
+    if jid < 0 {
+        return Err(JailError::JailSetError(
+            ::std::io::Error::last_os_error().to_string(),
+        ));
+    }
+
+    Ok(jid)
+}
+
+/// Resolve a jail `name` to its `jid` using `jail_getid(3)`.
+pub fn jail_getid(name: &str) -> Result<i32, JailError> {
+    let name = CString::new(name).map_err(|_| JailError::SerializeFailed)?;
+
+    let jid = unsafe { ::libc::jail_getid(name.as_ptr()) };
+
+    if jid < 0 {
+        return Err(JailError::JailGetError(
+            ::std::io::Error::last_os_error().to_string(),
+        ));
+    }
+
+    Ok(jid)
+}
+
+/// Remove (kill) the jail identified by `jid`.
+pub fn jail_remove(jid: i32) -> Result<(), JailError> {
+    let ret = unsafe { ::libc::jail_remove(jid) };
+
+    if ret != 0 {
+        return Err(JailError::JailRemoveFailed);
+    }
+
+    Ok(())
+}
+
+/// Run `jail_set(2)` against an already-assembled iovec array.
+pub fn jail_set(iovecs: &mut [iovec], flags: c_int) -> Result<i32, JailError> {
+    let jid = unsafe { ::libc::jail_set(iovecs.as_mut_ptr(), iovecs.len() as u32, flags) };
+
+    if jid < 0 {
+        return Err(JailError::JailSetError(
+            ::std::io::Error::last_os_error().to_string(),
+        ));
+    }
+
+    Ok(jid)
+}
+
+/// Run `jail_get(2)` against an already-assembled iovec array.
+pub fn jail_get(iovecs: &mut [iovec], flags: c_int) -> Result<i32, JailError> {
+    let jid = unsafe { ::libc::jail_get(iovecs.as_mut_ptr(), iovecs.len() as u32, flags) };
+
+    if jid < 0 {
+        return Err(JailError::JailGetError(
+            ::std::io::Error::last_os_error().to_string(),
+        ));
+    }
+
+    Ok(jid)
+}
+
+/// Fetch the next live `jid` after `lastjid`, the same technique `jls(8)`
+/// uses to enumerate every running jail. Returns `Ok(None)` once the kernel
+/// reports `ENOENT`, i.e. there is no jail after `lastjid`.
+pub fn jail_next(lastjid: i32) -> Result<Option<i32>, JailError> {
+    let mut lastjid_name = CString::new("lastjid").unwrap();
+    let mut lastjid_val = lastjid;
+    let mut jid_name = CString::new("jid").unwrap();
+    let mut jid_val: i32 = 0;
+
+    let mut iovecs = vec![
+        iovec {
+            iov_base: lastjid_name.as_ptr() as *mut _,
+            iov_len: lastjid_name.as_bytes_with_nul().len(),
+        },
+        iovec {
+            iov_base: &mut lastjid_val as *mut _ as *mut _,
+            iov_len: ::std::mem::size_of::<i32>(),
+        },
+        iovec {
+            iov_base: jid_name.as_ptr() as *mut _,
+            iov_len: jid_name.as_bytes_with_nul().len(),
+        },
+        iovec {
+            iov_base: &mut jid_val as *mut _ as *mut _,
+            iov_len: ::std::mem::size_of::<i32>(),
+        },
+    ];
+
+    let ret = unsafe { ::libc::jail_get(iovecs.as_mut_ptr(), iovecs.len() as u32, 0) };
+
+    if ret < 0 {
+        let err = ::std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(::libc::ENOENT) {
+            return Ok(None);
+        }
+
+        return Err(JailError::JailGetError(err.to_string()));
+    }
+
+    Ok(Some(jid_val))
+}
+
+/// Attach the calling process to the jail identified by `jid`, via
+/// `jail_attach(2)`.
+pub fn jail_attach(jid: i32) -> Result<(), JailError> {
+    let ret = unsafe { ::libc::jail_attach(jid) };
+
+    if ret != 0 {
+        return Err(JailError::AttachError(
+            ::std::io::Error::last_os_error().to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn null_iovec() -> iovec {
+    iovec {
+        iov_base: ptr::null_mut(),
+        iov_len: 0,
+    }
+}